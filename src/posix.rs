@@ -2,11 +2,11 @@ use std::cell::RefCell;
 use std::env;
 use std::ffi::{CString, OsStr, OsString};
 use std::fs::File;
-use std::io::{Error, Result};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 use std::iter;
 use std::mem;
 use std::os::unix::ffi::OsStrExt;
-use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::ptr;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
@@ -16,6 +16,12 @@ use libc::{c_char, c_int};
 
 use crate::os_common::{ExitStatus, StandardStream};
 
+extern "C" {
+    // Not exposed by the libc crate on every target; posix_spawn(p)
+    // needs it directly to inherit the caller's environment.
+    static environ: *mut *mut c_char;
+}
+
 pub use libc::{ECHILD, ENOSPC};
 
 fn check_err<T: Ord + Default>(num: T) -> Result<T> {
@@ -25,6 +31,18 @@ fn check_err<T: Ord + Default>(num: T) -> Result<T> {
     Ok(num)
 }
 
+// Unlike the rest of libc, the posix_spawn_file_actions_*/posix_spawnattr_*
+// family returns the error number directly (0 on success) instead of -1
+// with errno set - check_err()'s "negative means error, consult errno"
+// convention does not apply to them.
+fn check_spawn_err(errno: c_int) -> Result<()> {
+    if errno == 0 {
+        Ok(())
+    } else {
+        Err(Error::from_raw_os_error(errno))
+    }
+}
+
 pub fn pipe() -> Result<(File, File)> {
     let mut fds = [0 as c_int; 2];
     check_err(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
@@ -51,12 +69,59 @@ pub fn setgid(gid: u32) -> Result<()> {
     Ok(())
 }
 
+// Sets the full list of supplementary group IDs for the calling process.
+// Unlike setuid/setgid above, a caller that drops privileges but never
+// calls this keeps the parent's group memberships - a common privilege
+// separation bug.
+pub fn setgroups(groups: &[u32]) -> Result<()> {
+    let groups: Vec<libc::gid_t> = groups.iter().map(|&g| g as libc::gid_t).collect();
+    check_err(unsafe { libc::setgroups(groups.len(), groups.as_ptr()) })?;
+    Ok(())
+}
+
+// Resolves the supplementary groups a given user belongs to, the same
+// list `initgroups(3)` would install, without actually installing them.
+// Meant to be called before fork() so the NSS lookup (which allocates
+// and may talk to nsswitch backends) happens in the parent; the result
+// can then be handed to stage_exec so the child only has to call the
+// allocation-free setgroups() above.
+pub fn getgrouplist(user: &OsStr, gid: u32) -> Result<Vec<u32>> {
+    let user = os_to_cstring(user)?;
+    let gid = gid as libc::gid_t;
+
+    let mut ngroups: c_int = 16;
+    loop {
+        let mut groups: Vec<libc::gid_t> = vec![0; ngroups as usize];
+        let ret = unsafe {
+            libc::getgrouplist(
+                user.as_ptr(),
+                gid,
+                groups.as_mut_ptr(),
+                &mut ngroups as *mut c_int,
+            )
+        };
+        if ret >= 0 {
+            groups.truncate(ngroups as usize);
+            return Ok(groups);
+        }
+        // ngroups was updated in place with the required size - retry.
+    }
+}
+
 fn os_to_cstring(s: &OsStr) -> Result<CString> {
     // Like CString::new, but returns an io::Result for consistency with
     // everything else.
     CString::new(s.as_bytes()).map_err(|_| Error::from_raw_os_error(libc::EINVAL))
 }
 
+// execvp/posix_spawnp search PATH only when cmd has no '/' in it, and
+// stage_exec's own PATH walk (execve does no search) follows the same
+// rule; shared by stage_exec (fork() path) and posix_spawn (fast path) so
+// the two don't drift.
+fn needs_path_search(cmd: &OsStr) -> bool {
+    !cmd.as_bytes().contains(&b'/')
+}
+
 #[derive(Debug)]
 struct CVec {
     // Individual C strings.  Each element self.ptrs[i] points to the
@@ -130,10 +195,15 @@ fn split_path(path: &OsStr) -> SplitPath {
 
 #[cfg(test)]
 mod tests {
-    use super::split_path;
+    use super::{
+        can_posix_spawn, dup2, fork_exec, getgrouplist, pipe, recv_child_error, split_path,
+        waitpid, ExecOptions, PreExecHook, CHILD_ERROR_FOOTER,
+    };
     use std;
     use std::ffi::OsStr;
+    use std::io::{Read, Write};
     use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
 
     fn s(s: &str) -> Vec<&str> {
         split_path(OsStr::new(s))
@@ -155,6 +225,155 @@ mod tests {
         assert_eq!(s("a::b"), vec!["a", "b"]);
         assert_eq!(s(":a::::b:"), vec!["a", "b"]);
     }
+
+    #[test]
+    fn test_recv_child_error_clean_eof_means_success() {
+        let (r, w) = pipe().unwrap();
+        drop(w); // what a successful exec's CLOEXEC close looks like
+        assert!(recv_child_error(&r).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_recv_child_error_roundtrips_errno() {
+        let (r, mut w) = pipe().unwrap();
+        w.write_all(&libc::ENOENT.to_ne_bytes()).unwrap();
+        w.write_all(CHILD_ERROR_FOOTER).unwrap();
+        drop(w);
+        let err = recv_child_error(&r).unwrap().unwrap();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+    }
+
+    #[test]
+    fn test_recv_child_error_rejects_short_write() {
+        let (r, mut w) = pipe().unwrap();
+        w.write_all(&[1, 2, 3]).unwrap();
+        drop(w);
+        let err = recv_child_error(&r).unwrap().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_recv_child_error_rejects_bad_footer() {
+        let (r, mut w) = pipe().unwrap();
+        w.write_all(&0i32.to_ne_bytes()).unwrap();
+        w.write_all(b"XXXX").unwrap();
+        drop(w);
+        let err = recv_child_error(&r).unwrap().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_can_posix_spawn() {
+        assert!(can_posix_spawn(None, None, None, None, false));
+        assert!(!can_posix_spawn(Some(OsStr::new("/tmp")), None, None, None, false));
+        assert!(!can_posix_spawn(None, Some(0), None, None, false));
+        assert!(!can_posix_spawn(None, None, Some(0), None, false));
+        assert!(!can_posix_spawn(None, None, None, Some(&[0]), false));
+        assert!(!can_posix_spawn(None, None, None, None, true));
+    }
+
+    #[test]
+    fn test_getgrouplist_includes_primary_gid() {
+        // root is group 0 on every system that has a root account, and
+        // getgrouplist() always includes the gid it is given.  root is
+        // normally in fewer than 16 groups, so this usually returns from
+        // the first pass without going through the retry.
+        let groups = getgrouplist(OsStr::new("root"), 0).unwrap();
+        assert!(groups.contains(&0));
+    }
+
+    #[test]
+    fn test_fork_exec_cwd_runs_before_path_search_and_exec() {
+        let (out_r, out_w) = pipe().unwrap();
+        let out_w_fd = out_w.as_raw_fd();
+        let opts = ExecOptions {
+            cwd: Some(OsStr::new("/")),
+            pre_exec: Some(unsafe {
+                PreExecHook::new(move || dup2(out_w_fd, libc::STDOUT_FILENO))
+            }),
+            ..Default::default()
+        };
+        let pid = fork_exec("pwd", &["pwd"], None::<&[&str]>, opts).unwrap();
+        drop(out_w);
+
+        let mut output = String::new();
+        (&out_r).read_to_string(&mut output).unwrap();
+        waitpid(pid, 0).unwrap();
+        assert_eq!(output.trim(), "/");
+    }
+
+    #[test]
+    fn test_fork_exec_pre_exec_runs_before_image_is_replaced() {
+        let (marker_r, marker_w) = pipe().unwrap();
+        let marker_w_fd = marker_w.as_raw_fd();
+        let opts = ExecOptions {
+            pre_exec: Some(unsafe {
+                PreExecHook::new(move || {
+                    // /bin/true never touches this fd, so a marker byte
+                    // can only have gotten here from pre_exec itself -
+                    // and only before exec() replaced the child's image.
+                    let n = libc::write(marker_w_fd, b"M".as_ptr() as *const _, 1);
+                    if n != 1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                })
+            }),
+            ..Default::default()
+        };
+        let pid = fork_exec("true", &["true"], None::<&[&str]>, opts).unwrap();
+        drop(marker_w);
+
+        let mut marker = [0u8; 1];
+        (&marker_r).read_exact(&mut marker).unwrap();
+        waitpid(pid, 0).unwrap();
+        assert_eq!(&marker, b"M");
+    }
+
+    #[test]
+    fn test_fork_exec_drops_privileges_in_safe_order() {
+        // setuid() before setgid()/setgroups() would make both of the
+        // latter fail with EPERM (root's gid/groups-changing privilege is
+        // gone as soon as its uid is). Only runnable as root, since that's
+        // the only uid that can drop privileges at all.
+        if unsafe { libc::getuid() } != 0 {
+            eprintln!("skipping: test_fork_exec_drops_privileges_in_safe_order needs root");
+            return;
+        }
+
+        let (out_r, out_w) = pipe().unwrap();
+        let out_w_fd = out_w.as_raw_fd();
+        let nobody: u32 = 65534;
+        let opts = ExecOptions {
+            uid: Some(nobody),
+            gid: Some(nobody),
+            groups: Some(&[nobody]),
+            pre_exec: Some(unsafe {
+                PreExecHook::new(move || dup2(out_w_fd, libc::STDOUT_FILENO))
+            }),
+            ..Default::default()
+        };
+        let pid = fork_exec("id", &["id", "-u"], None::<&[&str]>, opts).unwrap();
+        drop(out_w);
+
+        let mut output = String::new();
+        (&out_r).read_to_string(&mut output).unwrap();
+        waitpid(pid, 0).unwrap();
+        assert_eq!(output.trim(), nobody.to_string());
+    }
+}
+
+type PreExecFn = Box<dyn FnMut() -> Result<()>>;
+
+// The post-fork personality of a child, bundled up so FinishExec::new()
+// doesn't grow another positional argument every time stage_exec() grows
+// another ExecOptions field.
+struct FinishExecOpts {
+    cwd: Option<CString>,
+    groups: Option<Vec<u32>>,
+    gid: Option<u32>,
+    uid: Option<u32>,
+    pre_exec: Option<PreExecFn>,
 }
 
 struct FinishExec {
@@ -162,6 +381,11 @@ struct FinishExec {
     argvec: CVec,
     envvec: Option<CVec>,
     search_path: Option<OsString>,
+    cwd: Option<CString>,
+    groups: Option<Vec<libc::gid_t>>,
+    gid: Option<libc::gid_t>,
+    uid: Option<libc::uid_t>,
+    pre_exec: Option<RefCell<PreExecFn>>,
 
     // Use of interior mutability for exe_buf makes it much easier to
     // implement and use FinishExec::set_exe.  Also, exe_buf only
@@ -176,6 +400,7 @@ impl FinishExec {
         argvec: CVec,
         envvec: Option<CVec>,
         search_path: Option<OsString>,
+        opts: FinishExecOpts,
     ) -> FinishExec {
         // Avoid allocation after fork() by pre-allocating the buffer
         // that will be used for constructing the executable C string.
@@ -194,6 +419,13 @@ impl FinishExec {
             argvec,
             envvec,
             search_path,
+            cwd: opts.cwd,
+            groups: opts
+                .groups
+                .map(|groups| groups.into_iter().map(|g| g as libc::gid_t).collect()),
+            gid: opts.gid.map(|gid| gid as libc::gid_t),
+            uid: opts.uid.map(|uid| uid as libc::uid_t),
+            pre_exec: opts.pre_exec.map(RefCell::new),
             exe_buf: RefCell::new(Vec::with_capacity(max_exe_len)),
         }
     }
@@ -201,6 +433,33 @@ impl FinishExec {
     fn finish(&self) -> Result<()> {
         // Invoked after fork() - no heap allocation allowed
 
+        if let Some(ref cwd) = self.cwd {
+            // Must happen before the PATH search below, since a relative
+            // PATH entry (or a relative cmd) is resolved against the new
+            // directory, not the one the parent was in.
+            check_err(unsafe { libc::chdir(cwd.as_ptr()) })?;
+        }
+
+        // Order matters: setgroups must run while we still have the
+        // privilege to change the group list, and setgid must run before
+        // setuid, since dropping the uid first would make both later
+        // calls fail with EPERM.
+        if let Some(ref groups) = self.groups {
+            check_err(unsafe { libc::setgroups(groups.len(), groups.as_ptr()) })?;
+        }
+        if let Some(gid) = self.gid {
+            check_err(unsafe { libc::setgid(gid) })?;
+        }
+        if let Some(uid) = self.uid {
+            check_err(unsafe { libc::setuid(uid) })?;
+        }
+
+        if let Some(ref pre_exec) = self.pre_exec {
+            // Runs last, immediately before exec, so it sees the final
+            // cwd/uid/gid the child will actually run with.
+            (pre_exec.borrow_mut())()?;
+        }
+
         if let Some(ref search_path) = self.search_path {
             // POSIX specifies execvp and execve, but not execvpe
             // (although glibc has one), so we have to iterate over
@@ -245,10 +504,45 @@ impl FinishExec {
     }
 }
 
+// An (unsafe) pre-exec hook, run in the child after fork()/chdir/the
+// uid-gid drop but immediately before exec.  Analogous to std's
+// CommandExt::pre_exec, this lets a caller do things the crate can't
+// anticipate - setsid() to detach into a new session, setpgid() to form
+// a process group, prctl(PR_SET_PDEATHSIG) so the child dies with the
+// parent, tightening an rlimit, and so on.
+pub struct PreExecHook(Box<dyn FnMut() -> Result<()>>);
+
+impl PreExecHook {
+    /// # Safety
+    ///
+    /// The closure runs in the child between fork() and exec(), in the
+    /// same fragile, allocation-free, signal-unsafe context documented on
+    /// FinishExec::finish() - it must restrict itself to async-signal-safe
+    /// operations, exactly as required of std's CommandExt::pre_exec.
+    pub unsafe fn new(f: impl FnMut() -> Result<()> + 'static) -> PreExecHook {
+        PreExecHook(Box::new(f))
+    }
+}
+
+// The parts of stage_exec's behavior that aren't needed by every caller
+// and that keep growing (cwd, uid/gid drop, ...) live here instead of as
+// more positional arguments on stage_exec itself.
+#[derive(Default)]
+pub struct ExecOptions<'a> {
+    pub cwd: Option<&'a OsStr>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    // Pre-collected, e.g. via getgrouplist(), since NSS lookups are not
+    // allowed after fork().
+    pub groups: Option<&'a [u32]>,
+    pub pre_exec: Option<PreExecHook>,
+}
+
 pub fn stage_exec(
     cmd: impl AsRef<OsStr>,
     args: &[impl AsRef<OsStr>],
     env: Option<&[impl AsRef<OsStr>]>,
+    opts: ExecOptions,
 ) -> Result<impl Fn() -> Result<()>> {
     let cmd = cmd.as_ref().to_owned();
     let argvec = CVec::new(args)?;
@@ -257,8 +551,14 @@ pub fn stage_exec(
     } else {
         None
     };
+    let cwd = if let Some(cwd) = opts.cwd {
+        Some(os_to_cstring(cwd)?)
+    } else {
+        None
+    };
+    let groups = opts.groups.map(|groups| groups.to_vec());
 
-    let search_path = if !cmd.as_bytes().iter().any(|&b| b == b'/') {
+    let search_path = if needs_path_search(&cmd) {
         env::var_os("PATH")
             // treat empty path as non-existent
             .and_then(|p| if p.len() == 0 { None } else { Some(p) })
@@ -266,10 +566,261 @@ pub fn stage_exec(
         None
     };
 
-    let exec = FinishExec::new(cmd, argvec, envvec, search_path);
+    let exec = FinishExec::new(
+        cmd,
+        argvec,
+        envvec,
+        search_path,
+        FinishExecOpts {
+            cwd,
+            groups,
+            gid: opts.gid,
+            uid: opts.uid,
+            pre_exec: opts.pre_exec.map(|hook| hook.0),
+        },
+    );
     Ok(move || exec.finish())
 }
 
+// Fixed footer appended after the 4-byte errno in the child error pipe, so
+// the parent can tell a well-formed message from a short or torn write.
+const CHILD_ERROR_FOOTER: &[u8; 4] = b"NOEX";
+
+// Writes the errno of a failed post-fork operation to the error pipe and
+// never returns - invoked only in the child, after which it must _exit()
+// without unwinding back into the caller's stack.
+fn send_child_error(mut err_pipe: &File, err: &Error) -> ! {
+    let mut msg = [0u8; 8];
+    msg[..4].copy_from_slice(&err.raw_os_error().unwrap_or(0).to_ne_bytes());
+    msg[4..].copy_from_slice(CHILD_ERROR_FOOTER);
+    // Best effort: if this write fails there is nothing left to do but
+    // exit and let the parent observe an undiagnosed child that exited
+    // with status 127.
+    let _ = err_pipe.write_all(&msg);
+    _exit(127);
+}
+
+// Reads the child's end of the error pipe to completion.  A clean EOF
+// means the child's CLOEXEC write fd was closed by a successful exec;
+// an 8-byte message means the child hit an error before exec and lets us
+// reconstruct it as a proper io::Error.
+fn recv_child_error(mut err_pipe: &File) -> Result<Option<Error>> {
+    let mut msg = [0u8; 8];
+    let mut filled = 0;
+    while filled < msg.len() {
+        match err_pipe.read(&mut msg[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    if filled == 0 {
+        return Ok(None);
+    }
+    if filled != msg.len() || &msg[4..] != CHILD_ERROR_FOOTER {
+        return Ok(Some(Error::other("child process sent a malformed exec error")));
+    }
+    let errno = i32::from_ne_bytes([msg[0], msg[1], msg[2], msg[3]]);
+    Ok(Some(Error::from_raw_os_error(errno)))
+}
+
+// fork()s and execs cmd/args/env, using a CLOEXEC self-pipe so that exec
+// failures in the child (a missing binary, a permission error) surface
+// to the caller as a proper Result::Err instead of silently producing a
+// child that immediately exits.
+pub fn fork_exec(
+    cmd: impl AsRef<OsStr>,
+    args: &[impl AsRef<OsStr>],
+    env: Option<&[impl AsRef<OsStr>]>,
+    opts: ExecOptions,
+) -> Result<u32> {
+    let exec = stage_exec(cmd, args, env, opts)?;
+    let (err_r, err_w) = pipe()?;
+    // Both ends must be CLOEXEC: the write end so the parent sees EOF on a
+    // successful exec, and the read end so it doesn't leak into the child
+    // we just exec'd (which never reads from it).
+    fcntl(err_r.as_raw_fd(), F_SETFD, Some(FD_CLOEXEC))?;
+    fcntl(err_w.as_raw_fd(), F_SETFD, Some(FD_CLOEXEC))?;
+
+    match unsafe { fork() }? {
+        None => {
+            // Child.  No heap allocation is allowed from here on beyond
+            // what send_child_error/exec() themselves need to report the
+            // one failure we can still observe.
+            reset_sigpipe().ok();
+            match exec() {
+                Ok(()) => unreachable!(),
+                Err(err) => send_child_error(&err_w, &err),
+            }
+        }
+        Some(pid) => {
+            // Parent.  Drop our copy of the write end first so that EOF
+            // on the read end actually signals "exec succeeded", rather
+            // than waiting for a descriptor we still hold open.
+            drop(err_w);
+            // Whatever recv_child_error() returns, the child has already
+            // been forked and must be reaped - including when the read
+            // itself fails, which would otherwise propagate straight out
+            // via `?` and leak pid as an unreaped zombie.
+            match recv_child_error(&err_r) {
+                Ok(None) => Ok(pid),
+                Ok(Some(err)) => {
+                    waitpid(pid, 0)?;
+                    Err(err)
+                }
+                Err(e) => {
+                    waitpid(pid, 0).ok();
+                    Err(e)
+                }
+            }
+        }
+    }
+}
+
+// Which fd (if any) should be dup2'd onto the child's stdin/stdout/stderr
+// before exec, for use with posix_spawn_file_actions_adddup2.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PosixSpawnRedirects {
+    pub stdin: Option<RawFd>,
+    pub stdout: Option<RawFd>,
+    pub stderr: Option<RawFd>,
+}
+
+// Whether the requested process features can be expressed with
+// posix_spawn(p).  Anything not representable by posix_spawn_file_actions_t
+// / posix_spawnattr_t (an explicit cwd, a uid/gid drop, supplementary
+// groups, or a pre-exec hook) forces the caller back onto the
+// fork()+FinishExec::finish() path.
+pub fn can_posix_spawn(
+    cwd: Option<&OsStr>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    groups: Option<&[u32]>,
+    has_pre_exec: bool,
+) -> bool {
+    cwd.is_none() && uid.is_none() && gid.is_none() && groups.is_none() && !has_pre_exec
+}
+
+// Fast path alongside fork()+FinishExec::finish().  posix_spawn(p) performs
+// the fork and exec as a single operation implemented by the libc (often
+// using clone() or vfork() under the hood), which is both faster than a
+// plain fork() on most libcs and avoids the hazards of calling fork() from
+// a multithreaded program.  Only usable when can_posix_spawn() says so;
+// otherwise the caller should fall back to stage_exec().
+pub fn posix_spawn(
+    cmd: impl AsRef<OsStr>,
+    args: &[impl AsRef<OsStr>],
+    env: Option<&[impl AsRef<OsStr>]>,
+    redirects: PosixSpawnRedirects,
+    close_fds: &[RawFd],
+) -> Result<u32> {
+    let cmd = cmd.as_ref();
+    let cmd_c = os_to_cstring(cmd)?;
+    let argvec = CVec::new(args)?;
+    let envvec = if let Some(env) = env {
+        Some(CVec::new(env)?)
+    } else {
+        None
+    };
+    let search_path = needs_path_search(cmd);
+
+    unsafe {
+        let mut file_actions: libc::posix_spawn_file_actions_t = mem::zeroed();
+        check_spawn_err(libc::posix_spawn_file_actions_init(&mut file_actions))?;
+
+        let actions_result = (|| {
+            for (target, fd) in [
+                (libc::STDIN_FILENO, redirects.stdin),
+                (libc::STDOUT_FILENO, redirects.stdout),
+                (libc::STDERR_FILENO, redirects.stderr),
+            ] {
+                if let Some(fd) = fd {
+                    check_spawn_err(libc::posix_spawn_file_actions_adddup2(
+                        &mut file_actions,
+                        fd,
+                        target,
+                    ))?;
+                }
+            }
+            for &fd in close_fds {
+                check_spawn_err(libc::posix_spawn_file_actions_addclose(&mut file_actions, fd))?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = actions_result {
+            libc::posix_spawn_file_actions_destroy(&mut file_actions);
+            return Err(e);
+        }
+
+        let mut attr: libc::posix_spawnattr_t = mem::zeroed();
+        check_spawn_err(libc::posix_spawnattr_init(&mut attr)).inspect_err(|_| {
+            libc::posix_spawn_file_actions_destroy(&mut file_actions);
+        })?;
+
+        let attr_result = (|| {
+            check_spawn_err(libc::posix_spawnattr_setflags(
+                &mut attr,
+                (libc::POSIX_SPAWN_SETSIGDEF | libc::POSIX_SPAWN_SETSIGMASK) as libc::c_short,
+            ))?;
+
+            // Reproduce what reset_sigpipe() does for the fork() path: the
+            // child runs with an empty signal mask and SIGPIPE reset to the
+            // default disposition.  sigemptyset/sigaddset are ordinary libc
+            // calls (-1/errno), unlike the posix_spawn* family above.
+            let mut empty_mask: libc::sigset_t = mem::zeroed();
+            check_err(libc::sigemptyset(&mut empty_mask))?;
+            check_spawn_err(libc::posix_spawnattr_setsigmask(&mut attr, &empty_mask))?;
+
+            let mut sigdefault: libc::sigset_t = mem::zeroed();
+            check_err(libc::sigemptyset(&mut sigdefault))?;
+            check_err(libc::sigaddset(&mut sigdefault, libc::SIGPIPE))?;
+            check_spawn_err(libc::posix_spawnattr_setsigdefault(&mut attr, &sigdefault))?;
+
+            Ok(())
+        })();
+        if let Err(e) = attr_result {
+            libc::posix_spawnattr_destroy(&mut attr);
+            libc::posix_spawn_file_actions_destroy(&mut file_actions);
+            return Err(e);
+        }
+
+        let mut pid: libc::pid_t = 0;
+        let argv = argvec.as_c_vec() as *const *mut c_char;
+        let envp = match envvec.as_ref() {
+            Some(envvec) => envvec.as_c_vec() as *const *mut c_char,
+            None => environ as *const *mut c_char,
+        };
+        let ret = if search_path {
+            libc::posix_spawnp(
+                &mut pid,
+                cmd_c.as_ptr(),
+                &file_actions,
+                &attr,
+                argv,
+                envp,
+            )
+        } else {
+            libc::posix_spawn(
+                &mut pid,
+                cmd_c.as_ptr(),
+                &file_actions,
+                &attr,
+                argv,
+                envp,
+            )
+        };
+
+        libc::posix_spawnattr_destroy(&mut attr);
+        libc::posix_spawn_file_actions_destroy(&mut file_actions);
+
+        if ret != 0 {
+            return Err(Error::from_raw_os_error(ret));
+        }
+        Ok(pid as u32)
+    }
+}
+
 pub fn _exit(status: u8) -> ! {
     unsafe { libc::_exit(status as c_int) }
 }